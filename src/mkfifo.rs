@@ -1,19 +1,24 @@
 use libc;
+use nix::sys::stat::Mode;
+use nix::unistd::mkfifo;
 
-use std::ffi::CString;
 use std::fs::{File, OpenOptions};
-use std::os::unix::fs::FileTypeExt;
-use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
 use std::path::Path;
 
+/// Open a FIFO read-only, creating it first if it doesn't exist yet.
+///
+/// Opened read-only rather than read-write: the poll loop now reacts to
+/// `POLLHUP` by reopening the FIFO instead of relying on a spare writer fd
+/// to suppress hangups, so there's no need to keep one open ourselves
+/// (which also used to let the daemon silently read back its own writes).
 pub fn open_fifo(path: &Path) -> Option<File> {
     let mut options = OpenOptions::new();
     options.read(true);
-    options.write(true);
+    // non-blocking so a stalled or partial writer can never wedge the
+    // single poll loop inside a blocking read()
+    options.custom_flags(libc::O_NONBLOCK);
 
-    // we open the file in read-write mode to prevent our poll()
-    // hack from sending us `POLLHUP`s when no process is at the
-    // other end of the pipe, so it blocks either way.
     match options.open(path) {
         Ok(f) => {
             match f.metadata().map(|m| m.file_type().is_fifo()) {
@@ -22,11 +27,14 @@ pub fn open_fifo(path: &Path) -> Option<File> {
             }
         }
         _ => {
-            let path_cstr = CString::new(path.as_os_str().as_bytes()).unwrap();
-            let path_ptr = path_cstr.as_ptr();
-            let perms = libc::S_IRUSR | libc::S_IWUSR;
-            let ret = unsafe { libc::mkfifo(path_ptr as *const i8, perms) };
-            if ret != 0 { None } else { options.open(path).ok() }
+            let perms = Mode::S_IRUSR | Mode::S_IWUSR;
+            match mkfifo(path, perms) {
+                Ok(()) => options.open(path).ok(),
+                Err(e) => {
+                    eprintln!("error: mkfifo({:?}) failed: {}", path, e);
+                    None
+                }
+            }
         }
     }
 }
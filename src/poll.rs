@@ -1,48 +1,110 @@
-use libc;
+use nix::errno::Errno;
+use nix::poll::{poll as nix_poll, PollFd, PollFlags};
 
 use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::os::unix::io::AsRawFd;
+use std::io::{ErrorKind, Read};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+/// Size of the scratch buffer used to drain a ready, non-blocking fd.
+const READ_CHUNK: usize = 4096;
 
 /// Set up a file to use with `poll`.
-pub fn setup_pollfd(fd: &File) -> libc::pollfd {
-    libc::pollfd {
-        fd: fd.as_raw_fd(),
-        events: libc::POLLIN,
-        revents: 0,
+pub fn setup_pollfd(fd: &File) -> PollFd {
+    setup_raw_pollfd(fd.as_raw_fd())
+}
+
+/// Set up a bare filedescriptor (e.g. a self-pipe's read end) to use with
+/// `poll`.
+pub fn setup_raw_pollfd(fd: RawFd) -> PollFd {
+    PollFd::new(fd, PollFlags::POLLIN)
+}
+
+/// What became of a polled fd, as far as callers need to react to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Readiness {
+    /// Data is available to read.
+    Readable,
+    /// The writing end went away (`POLLHUP`).
+    HangUp,
+    /// The fd is in an error state (`POLLERR`).
+    Error,
+    /// Nothing happened on this fd this cycle.
+    None,
+}
+
+/// Classify the `revents` nix reported for a polled fd.
+pub fn readiness(fd: &PollFd) -> Readiness {
+    let revents = fd.revents().unwrap_or_else(PollFlags::empty);
+
+    if revents.contains(PollFlags::POLLIN) {
+        Readiness::Readable
+    } else if revents.contains(PollFlags::POLLHUP) {
+        Readiness::HangUp
+    } else if revents.contains(PollFlags::POLLERR) {
+        Readiness::Error
+    } else {
+        Readiness::None
     }
 }
 
-/// Poll a set of filedescriptors perviously wrapped.
-pub fn poll(fds: &mut [libc::pollfd]) -> bool {
-    let poll_res = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as u64, -1) };
-    poll_res > 0
+/// Poll a set of filedescriptors previously wrapped.
+///
+/// `timeout_ms` is passed straight through to `poll(2)`: a negative value
+/// blocks indefinitely, `0` returns immediately, and a positive value waits
+/// at most that many milliseconds. A signal arriving mid-call (`EINTR`)
+/// doesn't bubble up as an error - it's silently retried, since it usually
+/// just means one of the daemon's own signal handlers ran, and those leave
+/// their mark in a self-pipe that the retried call then reports as readable.
+pub fn poll(fds: &mut [PollFd], timeout_ms: i32) -> Result<i32, Errno> {
+    loop {
+        match nix_poll(fds, timeout_ms) {
+            Ok(n) => return Ok(n),
+            Err(Errno::EINTR) => continue,
+            Err(e) => return Err(e),
+        }
+    }
 }
 
-/// A wrapped `BufReader` only yielding complete lines, annotated with
-/// an index.
-pub struct FileBuffer(pub BufReader<File>, pub usize);
+/// A non-blocking file paired with an index and an accumulator holding
+/// whatever incomplete line was left over from the previous poll cycle.
+pub struct FileBuffer(pub File, pub usize, pub Vec<u8>);
 
 /// Message type sent through our channels.
 pub type Message = Vec<(usize, String)>;
 
 /// Fill some buffers from a set of previously `poll`ed filedsecriptors.
-pub fn get_lines(fds: &[libc::pollfd], buffers: &mut [FileBuffer]) -> Message {
+///
+/// Each ready fd is drained in a loop until it reports `EWOULDBLOCK`, so a
+/// writer producing more than one chunk's worth of data per poll cycle
+/// doesn't get starved. Complete (`\n`-terminated) lines are split out and
+/// returned; any trailing partial line stays in the buffer's accumulator
+/// until a future call completes it.
+pub fn get_lines(fds: &[PollFd], buffers: &mut [FileBuffer]) -> Message {
+    // `fds` and `buffers` are always built and reordered together, so the
+    // two slices line up index-for-index.
     let fd_len = fds.len();
     let mut res = Vec::with_capacity(fd_len);
-    for (fd, &mut FileBuffer(ref mut reader, ref id)) in fds.iter().zip(buffers) {
-        if fd.fd != reader.get_ref().as_raw_fd() {
-            panic!("error: mismatched FileBuffer. this is a bug - please file an issue.");
+    let mut chunk = [0u8; READ_CHUNK];
+
+    for (fd, &mut FileBuffer(ref mut file, id, ref mut acc)) in fds.iter().zip(buffers) {
+        if readiness(fd) != Readiness::Readable {
+            continue;
         }
 
-        if fd.revents & libc::POLLIN != 0 {
-            let mut value = String::new();
-            if reader.read_line(&mut value).is_ok() {
-                if value.len() > 0 && value.as_bytes()[value.len() - 1] == 0xA {
-                    value.pop();
-                }
+        loop {
+            match file.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => acc.extend_from_slice(&chunk[..n]),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => break,
+            }
+        }
 
-                res.push((*id, value));
+        while let Some(pos) = acc.iter().position(|&b| b == 0xA) {
+            let line: Vec<u8> = acc.drain(..=pos).collect();
+            if let Ok(mut s) = String::from_utf8(line) {
+                s.pop(); // drop the trailing '\n'
+                res.push((id, s));
             }
         }
     }